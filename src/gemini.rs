@@ -0,0 +1,155 @@
+//! A minimal Gemini protocol listener, serving the same directory tree as
+//! the HTTP side, for capsule authors who also want live file serving.
+//!
+//! See the protocol specification at
+//! gemini://gemini.circumlunar.space/docs/specification.gmi
+
+use anyhow::{bail, ensure, Context, Result};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+// Use warp's re-export of http crate, to be sure we get the right version.
+use warp::http;
+
+use crate::{content_type_for, resolve_index, safe_join};
+
+/// Directory-index candidates, tried in order, mirroring `serve_file`'s
+/// `index.html` convention but preferring a capsule's own Gemini index.
+const INDEX_CANDIDATES: &[&str] = &["index.gmi", "index.html"];
+
+/// A Gemini request line is a CRLF-terminated absolute URL, at most 1024
+/// bytes including the CRLF.
+const MAX_REQUEST_LEN: usize = 1024;
+
+/// Listen for Gemini protocol connections on `addr`, serving files from
+/// `root`. Returns an error only if the TLS identity can't be built or
+/// `addr` can't be bound; after that, errors accepting a connection are
+/// logged and it keeps running until the process exits.
+pub async fn serve(addr: SocketAddr, root: PathBuf) -> Result<()> {
+    let acceptor = tls_acceptor()?;
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding Gemini listener on {}", addr))?;
+
+    println!("Serving Gemini at {:?}", addr);
+    println!("    Serving files from {}", root.display());
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(pair) => pair,
+            Err(error) => {
+                log::error!("error accepting Gemini connection: {}", error);
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let root = root.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(acceptor, stream, &root).await {
+                log::error!("error serving Gemini request from {}: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn handle_connection(acceptor: TlsAcceptor, stream: TcpStream, root: &Path) -> Result<()> {
+    let stream = acceptor.accept(stream).await?;
+    let mut reader = BufReader::new(stream);
+
+    // Bound the read itself, not just the length we're willing to accept
+    // afterwards: without this, a client that never sends a newline can
+    // make us buffer the request line without limit.
+    let mut request = String::new();
+    (&mut reader)
+        .take(MAX_REQUEST_LEN as u64)
+        .read_line(&mut request)
+        .await?;
+
+    let response = if !request.ends_with('\n') {
+        log::trace!("gemini: request line too long or unterminated");
+        b"59 Bad request\r\n".to_vec()
+    } else {
+        let request = request.trim_end_matches(['\r', '\n']);
+        match resolve_request(request, root) {
+            Ok(Some(path)) => match tokio::fs::read(&path).await {
+                Ok(body) => {
+                    let mut response = format!("20 {}\r\n", meta_for(&path)).into_bytes();
+                    response.extend_from_slice(&body);
+                    response
+                }
+                Err(error) => {
+                    log::trace!("gemini: {:?} not readable: {}", path, error);
+                    b"51 Not found\r\n".to_vec()
+                }
+            },
+            Ok(None) => {
+                log::trace!("gemini: {:?} has no index file", request);
+                b"51 Not found\r\n".to_vec()
+            }
+            Err(error) => {
+                log::trace!("gemini: bad request {:?}: {}", request, error);
+                b"59 Bad request\r\n".to_vec()
+            }
+        }
+    };
+
+    let mut stream = reader.into_inner();
+    stream.write_all(&response).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Parse a `gemini://host/path` request line and resolve it to a file
+/// under `root`, using the same path-safety rules `serve_file` uses for
+/// HTTP. A directory resolves to the first of `INDEX_CANDIDATES` it holds.
+///
+/// Returns `Err` for a malformed or unsafe request (→ `59 Bad request`),
+/// and `Ok(None)` for a directory with no index file to serve (→
+/// `51 Not found`) — distinct outcomes, since only the former is a
+/// protocol error.
+fn resolve_request(request: &str, root: &Path) -> Result<Option<PathBuf>> {
+    let uri: http::Uri = request.parse().context("not a valid URL")?;
+    ensure!(uri.scheme_str() == Some("gemini"), "not a gemini:// URL");
+
+    let path = safe_join(root, uri.path()).context("path escapes served directory")?;
+    if path.is_dir() {
+        return Ok(resolve_index(&path, INDEX_CANDIDATES));
+    }
+    Ok(Some(path))
+}
+
+/// The `<META>` line for a successful `20` response: `text/gemini` for
+/// `.gmi` files, and the same guess `serve_file` would make otherwise.
+fn meta_for(path: &Path) -> String {
+    match path.extension().and_then(std::ffi::OsStr::to_str) {
+        Some("gmi") => "text/gemini".to_string(),
+        _ => content_type_for(path),
+    }
+}
+
+/// Build a throwaway self-signed TLS identity for the Gemini listener.
+/// Gemini clients are expected to pin certificates on first use rather
+/// than rely on a CA, so a freshly generated certificate each run is
+/// normal, not a shortcut.
+fn tls_acceptor() -> Result<TlsAcceptor> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .context("generating self-signed certificate")?;
+    let cert_der = Certificate(cert.serialize_der()?);
+    let key_der = PrivateKey(cert.serialize_private_key_der());
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der], key_der);
+    let config = match config {
+        Ok(config) => config,
+        Err(error) => bail!("building TLS config: {}", error),
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -13,10 +13,38 @@ use warp::hyper::Body;
 use std::ffi::OsStr;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr as _;
+use std::sync::Arc;
 use std::{fs, net};
 
+mod gemini;
 mod stream_own;
 
+/// Join `request_path` onto `root`, rejecting any `..` or absolute-prefix
+/// component so that a request can't escape the served directory. Shared
+/// by the HTTP and Gemini servers, which both resolve requests against the
+/// same `root`.
+pub(crate) fn safe_join(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(request_path).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Find the first of `candidates` that exists as a file directly inside
+/// `dir`. Used by the Gemini server's directory-index handling;
+/// `serve_file` only ever looks for `index.html` and does not call this.
+pub(crate) fn resolve_index(dir: &Path, candidates: &[&str]) -> Option<PathBuf> {
+    candidates
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
 #[derive(FromArgs)]
 /// Serve a directory's contents, providing server-sent events when files are changed.
 struct ServeLive {
@@ -31,12 +59,53 @@ struct ServeLive {
     /// path for server-sent events reporting file changes. (Default: 'events')
     #[argh(option, default = r#"String::from("events")"#)]
     event_path: String,
+
+    /// glob pattern for paths to ignore when reporting file changes, relative
+    /// to the served directory. May be given more than once.
+    #[argh(option)]
+    ignore: Vec<String>,
+
+    /// file of gitignore-style patterns for paths to ignore when reporting
+    /// file changes, combined with any `--ignore` patterns.
+    #[argh(option)]
+    ignore_file: Option<String>,
+
+    /// quiet window, in milliseconds, to wait for no further changes before
+    /// flushing a coalesced `files-changed` event. (Default: 100)
+    #[argh(option, default = "100")]
+    debounce_ms: u64,
+
+    /// also serve the same directory over the Gemini protocol, listening
+    /// for TLS connections at this address (e.g. '0.0.0.0:1965').
+    #[argh(option)]
+    gemini: Option<net::SocketAddr>,
 }
 
 fn arg_address(arg: &str) -> net::SocketAddr {
     net::SocketAddr::from_str(arg).unwrap()
 }
 
+/// Build the matcher that decides which changed paths are too noisy to
+/// report as `files-changed` events, from the `--ignore` patterns and
+/// `--ignore-file` given on the command line. Patterns are matched against
+/// paths relative to `root`, with the usual gitignore `*`/`**` semantics.
+fn build_ignore_matcher(
+    root: &Path,
+    patterns: &[String],
+    ignore_file: Option<&str>,
+) -> Result<ignore::gitignore::Gitignore> {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    if let Some(ignore_file) = ignore_file {
+        if let Some(err) = builder.add(ignore_file) {
+            bail!("error reading ignore file {}: {}", ignore_file, err);
+        }
+    }
+    Ok(builder.build()?)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
@@ -51,6 +120,11 @@ async fn main() -> Result<()> {
         bail!("Not a directory: {}", root.display());
     }
     let root = fs::canonicalize(&root)?;
+    let ignore_matcher = Arc::new(build_ignore_matcher(
+        &root,
+        &args.ignore,
+        args.ignore_file.as_deref(),
+    )?);
 
     println!("Serving HTTP at {:?}", args.address);
     println!("    Serving files from {}", root.display());
@@ -58,18 +132,52 @@ async fn main() -> Result<()> {
     // Create a filter for server-sent events.
     let events_path = args.event_path;
     let root_clone = root.clone();
-    let events = warp::path(events_path)
-        .and(warp::get())
-        .map(move || result_to_response("server-sent event source", serve_events(&root_clone)));
+    let debounce = std::time::Duration::from_millis(args.debounce_ms);
+    let events = warp::path(events_path).and(warp::get()).map(move || {
+        result_to_response(
+            "server-sent event source",
+            serve_events(&root_clone, &ignore_matcher, debounce),
+        )
+    });
 
     // Create a filter for serving actual files.
     //
     // Not using warp::fs::dir because of
     // https://github.com/seanmonstar/warp/issues/953
     let base_uri = Uri::from_static("/");
-    let files = warp::path::tail().map(move |tail: warp::filters::path::Tail| {
-        result_to_response("file server", serve_file(tail, &base_uri, &root))
-    });
+    let files = warp::path::tail()
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .and_then(
+            move |tail: warp::filters::path::Tail,
+                  range: Option<String>,
+                  if_none_match: Option<String>,
+                  if_modified_since: Option<String>| {
+                let base_uri = base_uri.clone();
+                let root = root.clone();
+                async move {
+                    let request = FileRequest {
+                        tail,
+                        range: range.as_deref(),
+                        if_none_match: if_none_match.as_deref(),
+                        if_modified_since: if_modified_since.as_deref(),
+                    };
+                    let response = serve_file(request, &base_uri, &root).await;
+                    Ok::<_, std::convert::Infallible>(result_to_response("file server", response))
+                }
+            },
+        );
+
+    // Optionally serve the same tree over the Gemini protocol too.
+    if let Some(gemini_addr) = args.gemini {
+        let gemini_root = root.clone();
+        tokio::spawn(async move {
+            if let Err(error) = gemini::serve(gemini_addr, gemini_root).await {
+                log::error!("Gemini server error: {}", error);
+            }
+        });
+    }
 
     warp::serve(events.or(files)).run(args.address).await;
 
@@ -94,7 +202,12 @@ fn result_to_response<T: warp::Reply>(who: &str, result: Result<T>) -> warp::rep
     }
 }
 
-fn serve_events(dir: &Path) -> Result<warp::reply::Response> {
+fn serve_events(
+    dir: &Path,
+    ignore_matcher: &Arc<ignore::gitignore::Gitignore>,
+    debounce: std::time::Duration,
+) -> Result<warp::reply::Response> {
+    use std::collections::HashSet;
     use warp::sse;
 
     /// The type of server-sent `files-changed` events.
@@ -125,13 +238,14 @@ fn serve_events(dir: &Path) -> Result<warp::reply::Response> {
             .any(|c| matches!(c, Component::Normal(s) if s == ".git"))
     }
 
-    // Create an asynchronous channel for the `notify` watcher to send events
-    // on. It's a bounded channel, so we must notify the client of any events
-    // dropped due to backpressure.
-    let (mut tx, rx) = futures_channel::mpsc::channel(1);
+    // Create an asynchronous channel for the `notify` watcher to send
+    // batches of changed paths on. Raw events are cheap to accumulate, so
+    // this one is unbounded; backpressure is applied further downstream,
+    // where we flush one coalesced `files-changed` event per debounce
+    // window onto a bounded channel.
+    let (raw_tx, mut raw_rx) = futures_channel::mpsc::unbounded();
     let mut watcher = notify::recommended_watcher({
-        // True if the last send failed.
-        let mut dropped = false;
+        let ignore_matcher = ignore_matcher.clone();
 
         move |res: notify::Result<notify::Event>| match res {
             Err(error) => {
@@ -139,43 +253,32 @@ fn serve_events(dir: &Path) -> Result<warp::reply::Response> {
             }
             Ok(event) => {
                 log::trace!("event from file change monitor: {:?}", event);
-                let mut event = FilesChanged {
-                    paths: event.paths,
-                    dropped,
-                };
+                let mut paths = event.paths;
 
-                // Ignore changes to some files.
-                //
-                // Ideally this would be more configurable.
+                // Ignore changes to some files: the built-in editor-junk
+                // filters, plus whatever the user configured with
+                // `--ignore`/`--ignore-file`.
                 //
                 // I had an impulse that we should .gitignore files, but then I
                 // realized that's not the right set of files: many files that
                 // you would want listed in .gitignore are computation products
-                // that you do want to serve to the browser.
-                event.paths.retain(|path| {
-                    !is_auto_save(path) && !is_backup(path) && !is_git_metadata(path)
+                // that you do want to serve to the browser. So the built-in
+                // filters stay narrow, and it's up to the user to widen them.
+                paths.retain(|path| {
+                    !is_auto_save(path)
+                        && !is_backup(path)
+                        && !is_git_metadata(path)
+                        && !ignore_matcher
+                            .matched_path_or_any_parents(path, path.is_dir())
+                            .is_ignore()
                 });
-                if event.paths.is_empty() {
+                if paths.is_empty() {
                     log::trace!("    all changed filenames filtered out, event dropped");
                     return;
                 }
 
-                match serde_json::to_string(&event) {
-                    Ok(json) => match tx.try_send(json) {
-                        Ok(()) => {
-                            dropped = false;
-                        }
-                        Err(error) => {
-                            if error.is_full() {
-                                dropped = true;
-                            } else if !error.is_disconnected() {
-                                log::error!("error sending on channel: {}", error);
-                            }
-                        }
-                    },
-                    Err(error) => {
-                        log::error!("error serializing event: {}", error);
-                    }
+                if raw_tx.unbounded_send(paths).is_err() {
+                    log::trace!("    no one listening for raw change events, event dropped");
                 }
             }
         }
@@ -184,13 +287,73 @@ fn serve_events(dir: &Path) -> Result<warp::reply::Response> {
     log::trace!("created watcher");
     watcher.watch(dir, notify::RecursiveMode::Recursive)?;
 
-    // We can now treat `rx` as a stream of JSON-ified `notify`
-    // events. Convert that into a stream of `warp::sse::Event`s.
+    // Debounce and coalesce raw change batches: accumulate the changed
+    // paths into a set, and only serialize and forward a `FilesChanged`
+    // event once `debounce` has passed with no further changes. This
+    // turns a single save, or a build step touching many files, into one
+    // browser reload instead of a storm of them.
+    //
+    // `tx` is a bounded channel, so we must notify the client of any
+    // events dropped due to backpressure, same as before debouncing.
+    let (mut tx, rx) = futures_channel::mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut pending: HashSet<PathBuf> = HashSet::new();
+        let mut dropped = false;
+        let sleep = tokio::time::sleep(debounce);
+        tokio::pin!(sleep);
+        let mut timer_armed = false;
+
+        loop {
+            tokio::select! {
+                paths = raw_rx.next() => {
+                    match paths {
+                        Some(paths) => {
+                            pending.extend(paths);
+                            sleep.as_mut().reset(tokio::time::Instant::now() + debounce);
+                            timer_armed = true;
+                        }
+                        None => break,
+                    }
+                }
+                () = &mut sleep, if timer_armed => {
+                    timer_armed = false;
+                    if pending.is_empty() {
+                        continue;
+                    }
+                    let event = FilesChanged {
+                        paths: pending.drain().collect(),
+                        dropped,
+                    };
+                    match serde_json::to_string(&event) {
+                        Ok(json) => match tx.try_send(json) {
+                            Ok(()) => {
+                                dropped = false;
+                            }
+                            Err(error) => {
+                                if error.is_full() {
+                                    dropped = true;
+                                } else if !error.is_disconnected() {
+                                    log::error!("error sending on channel: {}", error);
+                                }
+                            }
+                        },
+                        Err(error) => {
+                            log::error!("error serializing event: {}", error);
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    // We can now treat `rx` as a stream of JSON-ified, debounced
+    // `FilesChanged` events. Convert that into a stream of
+    // `warp::sse::Event`s.
     //
-    // We try to do all filtering and fallible operations in the
-    // watcher's event handler above, so that this conversion never
-    // has to drop messages from `rx`, allowing us to use `map`
-    // in favor of `filter_map` and annoying error-catching.
+    // We try to do all filtering and fallible operations upstream, in the
+    // watcher's event handler and the debounce task above, so that this
+    // conversion never has to drop messages from `rx`, allowing us to use
+    // `map` in favor of `filter_map` and annoying error-catching.
     //
     // However, `sse::keep_alive` requires a stream of `Result`
     // items. Since we do not return errors, we need to spell out an
@@ -213,9 +376,322 @@ fn serve_events(dir: &Path) -> Result<warp::reply::Response> {
     .into_response())
 }
 
-fn serve_file(tail: warp::path::Tail, base_uri: &Uri, root: &Path) -> Result<Response<Body>> {
+/// A single byte range, inclusive on both ends, already clamped to fit
+/// within the file's length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Parse one comma-separated part of a `Range` header's `bytes=` spec.
+///
+/// Returns `Err` if `part` isn't valid `Range` syntax at all (the header
+/// should then be ignored, and the whole file served); returns `Ok(None)`
+/// if it parses but falls outside the file (the file doesn't have that
+/// range, so the header is "valid but unsatisfiable").
+fn parse_byte_range_part(part: &str, len: u64) -> Result<Option<ByteRange>, ()> {
+    let (start, end) = part.trim().split_once('-').ok_or(())?;
+    if start.is_empty() {
+        // "-suffixlen": the last `suffixlen` bytes of the file.
+        let suffix_len: u64 = end.parse().map_err(|_| ())?;
+        if suffix_len == 0 || len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(ByteRange {
+            start: len.saturating_sub(suffix_len),
+            end: len - 1,
+        }))
+    } else {
+        // "start-end" or the open-ended "start-".
+        let start: u64 = start.parse().map_err(|_| ())?;
+        let end = match end.is_empty() {
+            true => len.saturating_sub(1),
+            false => end
+                .parse::<u64>()
+                .map_err(|_| ())?
+                .min(len.saturating_sub(1)),
+        };
+        if start >= len || end < start {
+            return Ok(None);
+        }
+        Ok(Some(ByteRange { start, end }))
+    }
+}
+
+/// Parse a `Range` header's value into the byte ranges it requests.
+///
+/// Returns `None` if the header isn't valid `Range` syntax at all, in
+/// which case it should be ignored and the whole file served with a
+/// plain `200 OK`. Returns `Some(vec![])` if every part parsed but none
+/// of them overlap the file, which per
+/// [RFC 7233 §4.4](https://tools.ietf.org/html/rfc7233#section-4.4) the
+/// caller should reject with `416 Range Not Satisfiable`.
+fn parse_byte_ranges(header: &str, len: u64) -> Option<Vec<ByteRange>> {
+    let spec = header.strip_prefix("bytes=")?;
+    let ranges = spec
+        .split(',')
+        .map(|part| parse_byte_range_part(part, len))
+        .collect::<Result<Vec<Option<ByteRange>>, ()>>()
+        .ok()?
+        .into_iter()
+        .flatten()
+        .collect();
+    Some(ranges)
+}
+
+/// Whether `mime` is common enough text that we should tell the client
+/// it's UTF-8, mirroring actix-files' `PREFER_UTF8` behavior.
+fn prefer_utf8(mime: &mime::Mime) -> bool {
+    mime.type_() == mime::TEXT
+        || *mime == mime::APPLICATION_JAVASCRIPT
+        || *mime == mime::APPLICATION_JSON
+        || mime.essence_str() == "image/svg+xml"
+}
+
+/// Extensions for which `mime_guess` picks a type that's wrong, or at
+/// least unhelpful, for files we expect to serve. Checked before falling
+/// back to `mime_guess`'s own guess.
+const CONTENT_TYPE_OVERRIDES: &[(&str, &str)] = &[
+    // `mime_guess` matches ".mjs" against the system mime database's entry
+    // for MJ2 video, not JavaScript modules.
+    ("mjs", "text/javascript"),
+];
+
+/// Guess the `Content-Type` for `path` from its extension, appending
+/// `; charset=utf-8` for the common text types so editors and browsers
+/// don't have to guess the encoding of served source files.
+///
+/// Checks `CONTENT_TYPE_OVERRIDES` first; that's the place to add an
+/// override if some other extension ever needs one.
+pub(crate) fn content_type_for(path: &Path) -> String {
+    let extension = path.extension().and_then(OsStr::to_str);
+    let mime = extension
+        .and_then(|extension| {
+            CONTENT_TYPE_OVERRIDES
+                .iter()
+                .find(|(candidate, _)| *candidate == extension)
+                .map(|(_, mime)| mime.parse().expect("override mime type should be valid"))
+        })
+        .unwrap_or_else(|| mime_guess::from_path(path).first_or_octet_stream());
+    if prefer_utf8(&mime) {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+/// Read `range` out of the file at `path`. Does blocking I/O, so callers
+/// must run it via `tokio::task::spawn_blocking`.
+fn read_byte_range(path: &Path, range: ByteRange) -> Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let mut bytes = vec![0; range.len() as usize];
+    file.seek(SeekFrom::Start(range.start))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Build a `multipart/byteranges` body out of `ranges` of the file at
+/// `path`, whose total length is `len`. Does blocking I/O, so callers must
+/// run it via `tokio::task::spawn_blocking`.
+fn read_byte_ranges_multipart(
+    path: &Path,
+    content_type: &str,
+    len: u64,
+    ranges: &[ByteRange],
+    boundary: &str,
+) -> Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let mut body = Vec::new();
+    for range in ranges {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+        body.extend_from_slice(
+            format!(
+                "Content-Range: bytes {}-{}/{}\r\n\r\n",
+                range.start, range.end, len
+            )
+            .as_bytes(),
+        );
+
+        let mut part = vec![0; range.len() as usize];
+        file.seek(SeekFrom::Start(range.start))?;
+        file.read_exact(&mut part)?;
+        body.extend_from_slice(&part);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(body)
+}
+
+/// Build a response serving just `ranges` of the file at `path`, whose
+/// total length is `len`. A single range becomes a plain `206 Partial
+/// Content` body; more than one becomes a `multipart/byteranges` body, as
+/// described in [RFC 7233, Section 4.1](https://tools.ietf.org/html/rfc7233#section-4.1).
+///
+/// The actual reads happen on a blocking-pool thread via
+/// `tokio::task::spawn_blocking`, so this doesn't stall the async runtime
+/// the way a direct `std::fs` seek-and-read would.
+async fn serve_byte_ranges(
+    path: &Path,
+    content_type: &str,
+    len: u64,
+    ranges: &[ByteRange],
+    etag: &str,
+    last_modified: &str,
+) -> Result<Response<Body>> {
+    if let [range] = *ranges {
+        let owned_path = path.to_path_buf();
+        let bytes =
+            tokio::task::spawn_blocking(move || read_byte_range(&owned_path, range)).await??;
+
+        let response = Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Accept-Ranges", "bytes")
+            .header("ETag", etag)
+            .header("Last-Modified", last_modified)
+            .header("Content-Type", content_type)
+            .header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", range.start, range.end, len),
+            );
+        return Ok(response.body(bytes.into())?);
+    }
+
+    // More than one range: wrap each in its own part of a
+    // `multipart/byteranges` body.
+    let boundary = byteranges_boundary(path, ranges);
+    let body = {
+        let owned_path = path.to_path_buf();
+        let content_type = content_type.to_string();
+        let ranges = ranges.to_vec();
+        let boundary = boundary.clone();
+        tokio::task::spawn_blocking(move || {
+            read_byte_ranges_multipart(&owned_path, &content_type, len, &ranges, &boundary)
+        })
+        .await??
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header("Accept-Ranges", "bytes")
+        .header("ETag", etag)
+        .header("Last-Modified", last_modified)
+        .header(
+            "Content-Type",
+            format!("multipart/byteranges; boundary={}", boundary),
+        )
+        .body(body.into())?)
+}
+
+/// Derive a deterministic multipart boundary from the request, unlikely
+/// to collide with the ranges' own bytes. actix-files uses a random
+/// boundary instead; we don't have a random-number crate on hand, so hash
+/// the request, which is content-blind and so can't guarantee a
+/// collision is impossible, only unlikely.
+fn byteranges_boundary(path: &Path, ranges: &[ByteRange]) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    for range in ranges {
+        range.start.hash(&mut hasher);
+        range.end.hash(&mut hasher);
+    }
+    format!("serve-live-{:016x}", hasher.finish())
+}
+
+/// The request details `serve_file` cares about, bundled up because warp
+/// hands them to us as separate filter extractions.
+struct FileRequest<'a> {
+    tail: warp::filters::path::Tail,
+    range: Option<&'a str>,
+    if_none_match: Option<&'a str>,
+    if_modified_since: Option<&'a str>,
+}
+
+/// Compute the `ETag` this server reports for a file with the given
+/// metadata. Not a strong hash of the contents, just enough to notice a
+/// changed length or modification time, in the same spirit as actix-files'
+/// default `ETag`.
+fn etag_for(metadata: &fs::Metadata) -> Result<String> {
+    let modified = metadata.modified()?;
+    let since_epoch = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "\"{}-{}-{}\"",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+/// Return true if `if_none_match` or `if_modified_since` indicate the
+/// client already has the current version of the file described by `etag`
+/// and `modified`.
+fn is_not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: &str,
+    modified: std::time::SystemTime,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        return if_none_match
+            .split(',')
+            .any(|candidate| candidate.trim() == "*" || candidate.trim() == etag);
+    }
+    if let Some(if_modified_since) = if_modified_since {
+        if let Ok(since) = httpdate::parse_http_date(if_modified_since) {
+            // `Last-Modified` is only sent with second precision (see
+            // `fmt_http_date` below), so truncate `modified` the same way
+            // before comparing; otherwise a client echoing back the exact
+            // `Last-Modified` we sent would always be "earlier" than the
+            // sub-second-precise `modified` and never see a 304.
+            let modified =
+                httpdate::parse_http_date(&httpdate::fmt_http_date(modified)).unwrap_or(modified);
+            return modified <= since;
+        }
+    }
+    false
+}
+
+async fn serve_file(
+    request: FileRequest<'_>,
+    base_uri: &Uri,
+    root: &Path,
+) -> Result<Response<Body>> {
+    let FileRequest {
+        tail,
+        range,
+        if_none_match,
+        if_modified_since,
+    } = request;
+
     let query = String::new();
-    let mut path = root.join(tail.as_str());
+    let mut path = match safe_join(root, tail.as_str()) {
+        Some(path) => path,
+        None => {
+            log::error!(
+                "serve_file: rejected path escaping root: {:?}",
+                tail.as_str()
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("request failed".into())?);
+        }
+    };
 
     if path.is_dir() {
         let tail = tail.as_str();
@@ -232,22 +708,64 @@ fn serve_file(tail: warp::path::Tail, base_uri: &Uri, root: &Path) -> Result<Res
         }
     }
 
-    let mime_type = match path.extension().and_then(std::ffi::OsStr::to_str) {
-        Some("css") => Some("text/css"),
-        Some("html") => Some("text/html"),
-        Some("js") => Some("application/javascript"),
-        Some("png") => Some("image/png"),
-        _ => None,
+    let content_type = content_type_for(&path);
+
+    let metadata = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::error!("serve_file:");
+            log::error!("    tail: {:?}", tail);
+            log::error!("    path: {}", path.display());
+            log::error!("    error: {}", err);
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("request failed".into())?);
+        }
     };
+    let len = metadata.len();
+    let modified = metadata.modified()?;
+    let etag = etag_for(&metadata)?;
+    let last_modified = httpdate::fmt_http_date(modified);
+
+    if is_not_modified(if_none_match, if_modified_since, &etag, modified) {
+        log::trace!("{:?} not modified, returning 304", path);
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("ETag", &etag)
+            .header("Last-Modified", &last_modified)
+            .body(Body::empty())?);
+    }
 
-    match fs::read(&path) {
-        Ok(bytes) => {
-            let mut response = Response::builder().status(StatusCode::OK);
-            if let Some(mime_type) = mime_type {
-                response = response.header("Content-Type", mime_type);
+    if let Some(range_header) = range {
+        match parse_byte_ranges(range_header, len) {
+            // The header didn't look like a `bytes=` range; serve the
+            // whole file below, as if it weren't there.
+            None => {}
+            Some(ranges) if ranges.is_empty() => {
+                return Ok(Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header("Content-Range", format!("bytes */{}", len))
+                    .body(Body::empty())?);
             }
-            log::trace!("serving contents of {:?}", path);
-            Ok(response.body(bytes.into())?)
+            Some(ranges) => {
+                return serve_byte_ranges(&path, &content_type, len, &ranges, &etag, &last_modified)
+                    .await
+            }
+        }
+    }
+
+    match tokio::fs::File::open(&path).await {
+        Ok(file) => {
+            let stream = tokio_util::io::ReaderStream::new(file);
+            let response = Response::builder()
+                .status(StatusCode::OK)
+                .header("Accept-Ranges", "bytes")
+                .header("ETag", &etag)
+                .header("Last-Modified", &last_modified)
+                .header("Content-Type", &content_type)
+                .header("Content-Length", len);
+            log::trace!("streaming contents of {:?}", path);
+            Ok(response.body(Body::wrap_stream(stream))?)
         }
         Err(err) => {
             log::error!("serve_file:");
@@ -260,3 +778,75 @@ fn serve_file(tail: warp::path::Tail, base_uri: &Uri, root: &Path) -> Result<Res
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 10-byte file, so valid byte offsets run 0..=9.
+    const LEN: u64 = 10;
+
+    #[test]
+    fn whole_range() {
+        assert_eq!(
+            parse_byte_ranges("bytes=0-9", LEN),
+            Some(vec![ByteRange { start: 0, end: 9 }])
+        );
+    }
+
+    #[test]
+    fn open_ended_range_at_eof() {
+        // "start-" with start == len - 1 should grab just the last byte.
+        assert_eq!(
+            parse_byte_ranges("bytes=9-", LEN),
+            Some(vec![ByteRange { start: 9, end: 9 }])
+        );
+    }
+
+    #[test]
+    fn open_ended_range_past_eof_is_unsatisfiable() {
+        // "start-" with start == len is past the end of the file.
+        assert_eq!(parse_byte_ranges("bytes=10-", LEN), Some(vec![]));
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(
+            parse_byte_ranges("bytes=-3", LEN),
+            Some(vec![ByteRange { start: 7, end: 9 }])
+        );
+    }
+
+    #[test]
+    fn zero_length_suffix_is_unsatisfiable() {
+        assert_eq!(parse_byte_ranges("bytes=-0", LEN), Some(vec![]));
+    }
+
+    #[test]
+    fn backwards_range_is_unsatisfiable() {
+        assert_eq!(parse_byte_ranges("bytes=5-3", LEN), Some(vec![]));
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        assert_eq!(
+            parse_byte_ranges("bytes=0-1,5-6", LEN),
+            Some(vec![
+                ByteRange { start: 0, end: 1 },
+                ByteRange { start: 5, end: 6 },
+            ])
+        );
+    }
+
+    #[test]
+    fn unparseable_part_ignores_whole_header() {
+        // One bad part means the header isn't valid Range syntax at all,
+        // so the whole file should be served rather than 416.
+        assert_eq!(parse_byte_ranges("bytes=0-1,bad", LEN), None);
+    }
+
+    #[test]
+    fn missing_bytes_prefix_is_not_a_range_header() {
+        assert_eq!(parse_byte_ranges("0-1", LEN), None);
+    }
+}